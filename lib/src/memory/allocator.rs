@@ -0,0 +1,29 @@
+//! Pluggable buffer allocators, for queues whose backing memory should not
+//! come from the V4L2 driver itself (see `DMABuf` memory).
+use crate::device::queue::DrmModifier;
+use crate::fourcc::Fourcc;
+
+use super::DMABufSource;
+
+/// Something that can produce backing memory for buffers of a given size and
+/// pixel layout, for use as `DMABuf` queue memory.
+///
+/// This mirrors the `reqbufs`-driven allocation the queue performs for
+/// `MMAP`/`UserPtr` memory, but lets the backing storage come from an
+/// external allocator (e.g. a GBM/DRM device, see `GbmAllocator`) instead.
+pub trait Allocator {
+    /// Concrete buffer object type produced by this allocator.
+    type Buffer: DMABufSource + 'static;
+    /// Error type returned when allocation fails.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Allocate a single buffer able to hold a `width x height` frame encoded
+    /// as `fourcc`, laid out according to `modifier`.
+    fn alloc(
+        &self,
+        width: u32,
+        height: u32,
+        fourcc: Fourcc,
+        modifier: DrmModifier,
+    ) -> Result<Self::Buffer, Self::Error>;
+}
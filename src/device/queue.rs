@@ -4,14 +4,16 @@ pub mod qbuf;
 pub mod states;
 
 use super::Device;
+use crate::fourcc::Fourcc;
 use crate::ioctl;
+use crate::memory::allocator::Allocator;
 use crate::memory::*;
 use crate::*;
 use direction::*;
 use dqbuf::*;
 use qbuf::*;
 use states::*;
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
 use std::sync::{Arc, Mutex, Weak};
 
 /// Contains the handles (pointers to user memory or DMABUFs) that are kept
@@ -33,6 +35,13 @@ enum BufferState<M: Memory> {
     Dequeued,
 }
 
+/// Index of the first `true` (free) entry in `is_free`, isolated out of
+/// `try_get_free_buffer` as a pure function so its scanning logic can be
+/// exercised without a real device/queue.
+fn first_free_index(is_free: impl Iterator<Item = bool>) -> Option<usize> {
+    is_free.enumerate().find(|&(_, free)| free).map(|(i, _)| i)
+}
+
 /// Base values of a queue, that are always value no matter the state the queue
 /// is in. This base object remains alive as long as the queue is borrowed from
 /// the `Device`.
@@ -122,6 +131,34 @@ where
     }
 }
 
+/// A DRM format modifier, describing the physical layout (tiling,
+/// compression, ...) of a buffer beyond what its pixel format alone conveys.
+///
+/// This mirrors the `DRM_FORMAT_MOD_*` constants also exposed by V4L2 through
+/// `V4L2_PIX_FMT_MOD_*`, and lets a negotiated format be handed directly to a
+/// GBM/DRM consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrmModifier(u64);
+
+impl DrmModifier {
+    /// The buffer uses a simple, linear layout.
+    pub const LINEAR: DrmModifier = DrmModifier(0);
+    /// No explicit modifier has been negotiated; the layout is unknown.
+    pub const INVALID: DrmModifier = DrmModifier(0x00ff_ffff_ffff_ffff);
+}
+
+impl From<u64> for DrmModifier {
+    fn from(modifier: u64) -> Self {
+        DrmModifier(modifier)
+    }
+}
+
+impl From<DrmModifier> for u64 {
+    fn from(modifier: DrmModifier) -> Self {
+        modifier.0
+    }
+}
+
 /// Builder for a V4L2 format. This takes a mutable reference on the queue, so
 /// it is supposed to be short-lived: get one, adjust the format, and apply.
 pub struct FormatBuilder<'a> {
@@ -153,6 +190,17 @@ impl<'a> FormatBuilder<'a> {
         self
     }
 
+    /// Set the DRM format modifier describing the physical layout (tiling,
+    /// compression, ...) of the buffers for this format.
+    ///
+    /// Needed to negotiate a non-linear layout with drivers that expose
+    /// `V4L2_PIX_FMT_MOD_*` support, so that the resulting buffers can be
+    /// imported directly by a GBM/DRM consumer without being re-tiled.
+    pub fn set_modifier(mut self, modifier: impl Into<DrmModifier>) -> Self {
+        self.format.modifier = modifier.into();
+        self
+    }
+
     /// Apply the format built so far. The kernel will adjust the format to fit
     /// the driver's capabilities if needed, and the format actually applied will
     /// be returned.
@@ -233,6 +281,42 @@ impl<D: Direction> Queue<D, QueueInit> {
             },
         })
     }
+
+    /// Like `request_buffers::<DMABuf>()`, but also allocate one dmabuf-backed
+    /// buffer object per buffer from `allocator` (e.g. a `GbmAllocator`)
+    /// using the queue's currently negotiated format and modifier.
+    ///
+    /// No buffer is queued: each returned object still has to be wrapped
+    /// into a `DMABufHandle` and queued by the caller.
+    ///
+    /// The allocated buffer objects are returned alongside the queue instead
+    /// of being stored in it: the caller must keep them alive for as long as
+    /// the queue is used, since dropping one invalidates the dmabuf fd it
+    /// exported.
+    pub fn request_buffers_with_allocator<A: Allocator>(
+        self,
+        count: u32,
+        allocator: &A,
+    ) -> Result<(Queue<D, BuffersAllocated<DMABuf>>, Vec<A::Buffer>)> {
+        let format = self.get_format()?;
+        let fourcc =
+            Fourcc::try_from(format.pixelformat).map_err(|_| Error::UnsupportedFormat)?;
+
+        // REQBUFS is free to return more (or fewer) buffers than requested to
+        // satisfy the driver's own minimum, so allocate from the queue first
+        // and match `objects` to the buffer count it actually reports.
+        let queue = self.request_buffers::<DMABuf>(count)?;
+
+        let objects = (0..queue.num_buffers())
+            .map(|_| {
+                allocator
+                    .alloc(format.width, format.height, fourcc, format.modifier)
+                    .map_err(|e| Error::AllocatorError(Box::new(e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((queue, objects))
+    }
 }
 
 impl<D: Direction, M: Memory> Queue<D, BuffersAllocated<M>> {
@@ -369,6 +453,26 @@ impl<D: Direction, M: Memory> Queue<D, BuffersAllocated<M>> {
         Ok(QBuffer::new(self, id, num_planes, fuse))
     }
 
+    /// Look for the first buffer currently in the `Free` state and obtain it,
+    /// just like `get_buffer()` would.
+    ///
+    /// This spares the caller from having to track which buffer indices are
+    /// free themselves: just keep calling this method and queue whatever it
+    /// returns as part of an acquire -> queue -> dequeue -> reacquire cycle.
+    /// Returns `Ok(None)` if every buffer is currently queued or dequeued.
+    pub fn try_get_free_buffer<'a>(&'a mut self) -> Result<Option<QBuffer<'a, D, M>>> {
+        let free_id = {
+            let buffers_state = self.state.buffers_state.lock().unwrap();
+            first_free_index(
+                buffers_state
+                    .iter()
+                    .map(|state| matches!(state, BufferState::Free)),
+            )
+        };
+
+        free_id.map(|id| self.get_buffer(id)).transpose()
+    }
+
     /// Dequeue the next processed buffer and return it.
     ///
     /// The V4L2 buffer can not be reused until the returned `DQBuffer` is
@@ -395,6 +499,129 @@ impl<D: Direction, M: Memory> Queue<D, BuffersAllocated<M>> {
     }
 }
 
+/// Temporarily makes `fd` non-blocking, so that an ioctl issued on it (e.g.
+/// `VIDIOC_DQBUF`) returns `EAGAIN`/`Error::WouldBlock` instead of blocking
+/// the calling thread when it is not ready, as required for `AsyncFd::try_io`
+/// to be able to tell "not ready yet" apart from "the ioctl actually failed".
+///
+/// `QueueBase::fd` is shared with this queue's sibling queues on the same
+/// `Device` (e.g. the OUTPUT and CAPTURE queues of an M2M device), whose
+/// blocking `dequeue()` relies on `O_NONBLOCK` being unset. The flags are
+/// therefore saved on construction and restored on drop, rather than left
+/// flipped for the lifetime of the fd.
+#[cfg(feature = "async")]
+struct NonBlockingGuard {
+    fd: RawFd,
+    previous_flags: nix::fcntl::OFlag,
+}
+
+#[cfg(feature = "async")]
+impl NonBlockingGuard {
+    fn new(fd: RawFd) -> Result<Self> {
+        use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+        let previous_flags =
+            OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL).map_err(Error::from)?);
+        fcntl(fd, FcntlArg::F_SETFL(previous_flags | OFlag::O_NONBLOCK)).map_err(Error::from)?;
+
+        Ok(Self { fd, previous_flags })
+    }
+}
+
+#[cfg(feature = "async")]
+impl Drop for NonBlockingGuard {
+    fn drop(&mut self) {
+        use nix::fcntl::{fcntl, FcntlArg};
+
+        // Best-effort: there is nothing sensible to do with this error in a
+        // `Drop` impl, and leaving `fd` non-blocking is the lesser evil.
+        let _ = fcntl(self.fd, FcntlArg::F_SETFL(self.previous_flags));
+    }
+}
+
+/// Drives `dequeue` to completion by waiting for `fd` to report `interest`
+/// and retrying through `AsyncFd::try_io` until it no longer reports
+/// `Error::WouldBlock`, instead of assuming a single readiness notification
+/// means the ioctl won't block. Any other error is returned as is.
+#[cfg(feature = "async")]
+async fn wait_and_dequeue<M: Memory>(
+    fd: RawFd,
+    interest: tokio::io::Interest,
+    dequeue: impl Fn() -> Result<DQBuffer<M>>,
+) -> Result<DQBuffer<M>> {
+    use tokio::io::unix::AsyncFd;
+
+    let _nonblocking = NonBlockingGuard::new(fd)?;
+    let async_fd = AsyncFd::with_interest(fd, interest).map_err(Error::from)?;
+
+    loop {
+        let mut guard = async_fd.ready(interest).await.map_err(Error::from)?;
+
+        // `try_io` intercepts a `WouldBlock` returned by the closure, clears
+        // the readiness state and hands back `Err` so we retry; any other
+        // outcome is the real result of the ioctl.
+        match guard.try_io(|_| match dequeue() {
+            Err(Error::WouldBlock) => Err(std::io::ErrorKind::WouldBlock.into()),
+            other => Ok(other),
+        }) {
+            Ok(Ok(result)) => return result,
+            Ok(Err(_io_error)) => {
+                unreachable!(
+                    "dequeue() only ever surfaces WouldBlock as an io::Error, which try_io intercepts"
+                )
+            }
+            Err(_would_block) => continue,
+        }
+    }
+}
+
+impl<M: Memory> Queue<Capture, BuffersAllocated<M>> {
+    /// Asynchronous variant of `dequeue()`.
+    ///
+    /// Instead of blocking the calling thread until a buffer is ready, this
+    /// registers the queue's file descriptor (see `AsRawFd` on `QueueBase`)
+    /// with the async runtime's reactor and parks the task until the kernel
+    /// signals that one can be dequeued. This lets a single thread drive
+    /// several queues, possibly spread across several devices, cooperatively
+    /// instead of dedicating a blocking thread to each of them.
+    #[cfg(feature = "async")]
+    pub async fn dequeue_async(&self) -> Result<DQBuffer<M>> {
+        wait_and_dequeue(self.inner.fd, tokio::io::Interest::READABLE, || {
+            self.dequeue()
+        })
+        .await
+    }
+}
+
+impl<M: Memory> Queue<Output, BuffersAllocated<M>> {
+    /// Asynchronous variant of `dequeue()`. See
+    /// `Queue<Capture, _>::dequeue_async` for details.
+    #[cfg(feature = "async")]
+    pub async fn dequeue_async(&self) -> Result<DQBuffer<M>> {
+        wait_and_dequeue(self.inner.fd, tokio::io::Interest::WRITABLE, || {
+            self.dequeue()
+        })
+        .await
+    }
+}
+
+impl<D: Direction> Queue<D, BuffersAllocated<Mmap>> {
+    /// Export the buffer at `index` as a set of DMABUF file descriptors, one
+    /// per plane, using `VIDIOC_EXPBUF`.
+    ///
+    /// The returned `OwnedFd`s do not implement `DMABufSource` themselves;
+    /// once converted into a `File` (which does), each can be wrapped into a
+    /// `DMABufHandle` and queued on another device's queue, allowing this
+    /// MMAP-allocated buffer to be shared without any CPU copy.
+    pub fn export_buffer(&self, index: usize) -> Result<Vec<OwnedFd>> {
+        let num_planes = self.state.buffer_features.planes.len();
+
+        (0..num_planes)
+            .map(|plane| ioctl::expbuf(&self.inner, self.inner.type_, index, plane))
+            .collect()
+    }
+}
+
 /// A fuse that will return the buffer to the Free state when destroyed, unless
 /// it has been disarmed.
 // TODO Use Arc::Weak<Mutex<BufferState>> here to make DQBuffer passable across threads?
@@ -431,4 +658,29 @@ impl<M: Memory> Drop for BufferStateFuse<M> {
             }
         };
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drm_modifier_constants_roundtrip() {
+        assert_eq!(u64::from(DrmModifier::LINEAR), 0);
+        assert_eq!(u64::from(DrmModifier::INVALID), 0x00ff_ffff_ffff_ffff);
+        assert_eq!(DrmModifier::from(0x1234), DrmModifier(0x1234));
+    }
+
+    #[test]
+    fn first_free_index_finds_first_free_slot() {
+        assert_eq!(
+            first_free_index(vec![false, false, true, true].into_iter()),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn first_free_index_none_when_all_busy() {
+        assert_eq!(first_free_index(vec![false, false].into_iter()), None);
+    }
+}
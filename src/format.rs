@@ -0,0 +1,25 @@
+//! The V4L2 format negotiated on a queue: frame dimensions, pixel encoding,
+//! and the physical buffer layout used to describe it to external
+//! GBM/DRM consumers.
+use crate::device::queue::DrmModifier;
+use crate::PixelFormat;
+
+/// A V4L2 format, as negotiated through `G_FMT`/`S_FMT`/`TRY_FMT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Format {
+    pub width: u32,
+    pub height: u32,
+    pub pixelformat: PixelFormat,
+    /// Physical layout of the buffers (tiling, compression, ...) beyond what
+    /// `pixelformat` alone conveys. `DrmModifier::LINEAR` unless a GBM/DRM
+    /// consumer negotiated a tiled layout via `FormatBuilder::set_modifier`.
+    pub modifier: DrmModifier,
+}
+
+impl Format {
+    /// The DRM format modifier describing this format's physical buffer
+    /// layout.
+    pub fn modifier(&self) -> DrmModifier {
+        self.modifier
+    }
+}
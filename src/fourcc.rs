@@ -0,0 +1,140 @@
+//! DRM `Fourcc` pixel format codes and their mapping to/from `PixelFormat`.
+use std::fmt;
+
+use crate::PixelFormat;
+
+/// Build a little-endian fourcc code out of its four ASCII characters, the
+/// same way V4L2 and DRM both do.
+macro_rules! fourcc {
+    ($a:expr, $b:expr, $c:expr, $d:expr) => {
+        ($a as u32) | (($b as u32) << 8) | (($c as u32) << 16) | (($d as u32) << 24)
+    };
+}
+
+/// A DRM `fourcc` pixel format code, as used by GBM/DRM allocators.
+///
+/// DRM and V4L2 fourccs are both 4-byte ASCII codes and agree for the large
+/// majority of formats, but a handful diverge (e.g. the multiplanar NV12
+/// variants). `Fourcc` provides a checked mapping to and from `PixelFormat`
+/// so buffers can be shared between a V4L2 device and a GBM/DRM allocator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fourcc(u32);
+
+impl Fourcc {
+    pub const fn new(code: u32) -> Self {
+        Fourcc(code)
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        self.0
+    }
+
+    pub const ARGB8888: Fourcc = Fourcc(fourcc!(b'A', b'R', b'2', b'4'));
+    pub const XRGB8888: Fourcc = Fourcc(fourcc!(b'X', b'R', b'2', b'4'));
+    pub const NV12: Fourcc = Fourcc(fourcc!(b'N', b'V', b'1', b'2'));
+    pub const YUYV: Fourcc = Fourcc(fourcc!(b'Y', b'U', b'Y', b'V'));
+}
+
+/// `V4L2_PIX_FMT_NV12M`: the multiplanar variant of NV12 used by `_MPLANE`
+/// queues. It has no DRM counterpart of its own; it maps to the same
+/// `Fourcc::NV12` as the single-planar `V4L2_PIX_FMT_NV12`.
+const V4L2_PIX_FMT_NV12M: u32 = fourcc!(b'N', b'M', b'1', b'2');
+
+/// All the DRM fourccs `Fourcc` knows how to represent. A `PixelFormat` whose
+/// code isn't one of these (e.g. a V4L2-only code with no DRM equivalent, or
+/// an unrecognized value) has no valid `Fourcc` and must be rejected rather
+/// than passed through as a bogus one.
+const KNOWN_FOURCCS: &[Fourcc] = &[
+    Fourcc::ARGB8888,
+    Fourcc::XRGB8888,
+    Fourcc::NV12,
+    Fourcc::YUYV,
+];
+
+impl fmt::Display for Fourcc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.0.to_le_bytes();
+        for b in bytes {
+            write!(f, "{}", b as char)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when a `PixelFormat` has no known `Fourcc` equivalent. The
+/// `Fourcc` to `PixelFormat` direction cannot fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmappedFourccError(u32);
+
+impl fmt::Display for UnmappedFourccError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no known mapping for fourcc 0x{:08x}", self.0)
+    }
+}
+
+impl std::error::Error for UnmappedFourccError {}
+
+impl From<Fourcc> for PixelFormat {
+    fn from(fourcc: Fourcc) -> Self {
+        // DRM and V4L2 fourccs share the same byte encoding for the large
+        // majority of formats, `Fourcc::NV12` included, so no translation is
+        // needed on this direction; the divergent codes are multiplanar V4L2
+        // variants that have no DRM fourcc of their own, and are therefore
+        // only ever produced by the `TryFrom<PixelFormat>` direction below.
+        PixelFormat::from(fourcc.0)
+    }
+}
+
+impl TryFrom<PixelFormat> for Fourcc {
+    type Error = UnmappedFourccError;
+
+    fn try_from(pixel_format: PixelFormat) -> Result<Self, Self::Error> {
+        let code: u32 = pixel_format.into();
+
+        let fourcc = match code {
+            // Multiplanar variants collapse onto their single-planar DRM
+            // equivalent: DRM has no notion of "the planes are separate
+            // dmabufs", that's conveyed out-of-band by the buffer itself.
+            V4L2_PIX_FMT_NV12M => Fourcc::NV12,
+            code => Fourcc(code),
+        };
+
+        // Reject anything that isn't one of the fourccs we actually know
+        // about, rather than handing out a `Fourcc` with no real DRM meaning.
+        if KNOWN_FOURCCS.contains(&fourcc) {
+            Ok(fourcc)
+        } else {
+            Err(UnmappedFourccError(code))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_fourccs_roundtrip() {
+        for &fourcc in KNOWN_FOURCCS {
+            let pixel_format: PixelFormat = fourcc.into();
+            assert_eq!(Fourcc::try_from(pixel_format), Ok(fourcc));
+        }
+    }
+
+    #[test]
+    fn nv12m_maps_to_nv12() {
+        let pixel_format = PixelFormat::from(V4L2_PIX_FMT_NV12M);
+        assert_eq!(Fourcc::try_from(pixel_format), Ok(Fourcc::NV12));
+    }
+
+    #[test]
+    fn unmapped_code_is_rejected() {
+        let bogus = PixelFormat::from(fourcc!(b'B', b'O', b'G', b'U'));
+        assert!(Fourcc::try_from(bogus).is_err());
+    }
+
+    #[test]
+    fn zero_is_rejected() {
+        assert!(Fourcc::try_from(PixelFormat::from(0)).is_err());
+    }
+}
@@ -0,0 +1,32 @@
+//! Safe wrapper for the `VIDIOC_EXPBUF` ioctl.
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::bindings;
+use crate::device::queue::QueueType;
+use crate::Result;
+
+nix::ioctl_readwrite!(vidioc_expbuf, b'V', 16, bindings::v4l2_exportbuffer);
+
+/// Export plane `plane` of the buffer at `index` in `queue_type` as a DMABUF
+/// file descriptor, via `VIDIOC_EXPBUF`.
+pub fn expbuf(
+    fd: &impl AsRawFd,
+    queue_type: QueueType,
+    index: usize,
+    plane: usize,
+) -> Result<OwnedFd> {
+    let mut expbuf = bindings::v4l2_exportbuffer {
+        type_: queue_type as u32,
+        index: index as u32,
+        plane: plane as u32,
+        flags: libc::O_CLOEXEC as u32,
+        fd: -1,
+        ..Default::default()
+    };
+
+    unsafe { vidioc_expbuf(fd.as_raw_fd(), &mut expbuf)? };
+
+    // Safe because the kernel returned a valid, owned file descriptor upon
+    // success.
+    Ok(unsafe { OwnedFd::from_raw_fd(expbuf.fd) })
+}
@@ -0,0 +1,110 @@
+//! Safe wrappers for the `VIDIOC_G_FMT`, `VIDIOC_S_FMT` and `VIDIOC_TRY_FMT`
+//! ioctls.
+use std::os::unix::io::AsRawFd;
+
+use crate::bindings;
+use crate::device::queue::{DrmModifier, QueueType};
+use crate::format::Format;
+use crate::{Error, PixelFormat, Result};
+
+nix::ioctl_readwrite!(vidioc_g_fmt, b'V', 4, bindings::v4l2_format);
+nix::ioctl_readwrite!(vidioc_s_fmt, b'V', 5, bindings::v4l2_format);
+nix::ioctl_readwrite!(vidioc_try_fmt, b'V', 64, bindings::v4l2_format);
+
+/// Whether `queue_type` uses the multiplanar `v4l2_format.fmt.pix_mp` union
+/// member, as opposed to the single-planar `fmt.pix`. Only `pix_mp` carries
+/// a format modifier.
+fn is_multiplanar(queue_type: QueueType) -> bool {
+    matches!(
+        queue_type,
+        QueueType::VideoOutputMplane | QueueType::VideoCaptureMplane
+    )
+}
+
+fn to_v4l2_format(queue_type: QueueType, format: &Format) -> Result<bindings::v4l2_format> {
+    let mut v4l2_format = bindings::v4l2_format {
+        type_: queue_type as u32,
+        ..Default::default()
+    };
+
+    if is_multiplanar(queue_type) {
+        // Safe because `pix_mp` is the active member of the `fmt` union for
+        // the `_MPLANE` queue types.
+        let pix_mp = unsafe { &mut v4l2_format.fmt.pix_mp };
+        pix_mp.width = format.width;
+        pix_mp.height = format.height;
+        pix_mp.pixelformat = format.pixelformat.into();
+        pix_mp.modifier = u64::from(format.modifier);
+    } else {
+        // The single-planar `v4l2_pix_format` has no modifier field, so a
+        // non-linear layout can never be negotiated on these queue types.
+        if format.modifier != DrmModifier::LINEAR {
+            return Err(Error::UnsupportedFormat);
+        }
+
+        // Safe because `pix` is the active member of the `fmt` union for the
+        // non-`_MPLANE` queue types.
+        let pix = unsafe { &mut v4l2_format.fmt.pix };
+        pix.width = format.width;
+        pix.height = format.height;
+        pix.pixelformat = format.pixelformat.into();
+    }
+
+    Ok(v4l2_format)
+}
+
+fn from_v4l2_format(queue_type: QueueType, v4l2_format: &bindings::v4l2_format) -> Format {
+    if is_multiplanar(queue_type) {
+        // Safe: see `to_v4l2_format`.
+        let pix_mp = unsafe { &v4l2_format.fmt.pix_mp };
+
+        Format {
+            width: pix_mp.width,
+            height: pix_mp.height,
+            pixelformat: PixelFormat::from(pix_mp.pixelformat),
+            modifier: DrmModifier::from(pix_mp.modifier),
+        }
+    } else {
+        // Safe: see `to_v4l2_format`.
+        let pix = unsafe { &v4l2_format.fmt.pix };
+
+        Format {
+            width: pix.width,
+            height: pix.height,
+            pixelformat: PixelFormat::from(pix.pixelformat),
+            modifier: DrmModifier::LINEAR,
+        }
+    }
+}
+
+/// `VIDIOC_G_FMT`: retrieve the format currently active on `queue_type`.
+pub fn g_fmt(fd: &impl AsRawFd, queue_type: QueueType) -> Result<Format> {
+    let mut v4l2_format = bindings::v4l2_format {
+        type_: queue_type as u32,
+        ..Default::default()
+    };
+
+    unsafe { vidioc_g_fmt(fd.as_raw_fd(), &mut v4l2_format)? };
+
+    Ok(from_v4l2_format(queue_type, &v4l2_format))
+}
+
+/// `VIDIOC_S_FMT`: apply `format` to `queue_type`, returning what the driver
+/// actually configured (which may differ from what was requested).
+pub fn s_fmt(fd: &impl AsRawFd, queue_type: QueueType, format: Format) -> Result<Format> {
+    let mut v4l2_format = to_v4l2_format(queue_type, &format)?;
+
+    unsafe { vidioc_s_fmt(fd.as_raw_fd(), &mut v4l2_format)? };
+
+    Ok(from_v4l2_format(queue_type, &v4l2_format))
+}
+
+/// `VIDIOC_TRY_FMT`: like `s_fmt`, but without actually applying `format` to
+/// the queue.
+pub fn try_fmt(fd: &impl AsRawFd, queue_type: QueueType, format: Format) -> Result<Format> {
+    let mut v4l2_format = to_v4l2_format(queue_type, &format)?;
+
+    unsafe { vidioc_try_fmt(fd.as_raw_fd(), &mut v4l2_format)? };
+
+    Ok(from_v4l2_format(queue_type, &v4l2_format))
+}